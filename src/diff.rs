@@ -0,0 +1,84 @@
+use crate::{
+  insert_edges, insert_locations, insert_nodes, open_db, read_heap_file, run_migrations,
+  setup_db_if_needed, ConnectionOptions,
+};
+use rusqlite::Connection;
+use std::path::Path;
+
+// snapshot_id tags for the two captures stored in the combined db3.
+const BASELINE_SNAPSHOT_ID: i64 = 0;
+const CURRENT_SNAPSHOT_ID: i64 = 1;
+
+pub fn diff_db_name(current_heap: &str, baseline_heap: &str) -> String {
+  let current = Path::new(current_heap)
+    .file_stem()
+    .unwrap()
+    .to_str()
+    .unwrap();
+  let baseline = Path::new(baseline_heap)
+    .file_stem()
+    .unwrap()
+    .to_str()
+    .unwrap();
+  format!("{}.vs.{}.db3", current, baseline)
+}
+
+// node ids are stable across snapshots taken in the same isolate, so a node
+// id present in both captures is the same live object -- that's what the
+// `survived` classification below keys on.
+pub fn diff_snapshots(current_heap: &str, baseline_heap: &str) -> Connection {
+  let db_name = diff_db_name(current_heap, baseline_heap);
+  let needs_import = setup_db_if_needed(&db_name);
+
+  let mut conn = open_db(&db_name);
+  run_migrations(&mut conn);
+
+  if needs_import {
+    ConnectionOptions::for_import().apply(&conn);
+
+    let baseline_json = read_heap_file(baseline_heap);
+    insert_nodes(&baseline_json, &mut conn, BASELINE_SNAPSHOT_ID);
+    insert_edges(&baseline_json, &mut conn, BASELINE_SNAPSHOT_ID);
+    insert_locations(&baseline_json, &mut conn, BASELINE_SNAPSHOT_ID);
+
+    let current_json = read_heap_file(current_heap);
+    insert_nodes(&current_json, &mut conn, CURRENT_SNAPSHOT_ID);
+    insert_edges(&current_json, &mut conn, CURRENT_SNAPSHOT_ID);
+    insert_locations(&current_json, &mut conn, CURRENT_SNAPSHOT_ID);
+
+    build_delta(&conn);
+    ConnectionOptions::for_query().apply(&conn);
+  }
+
+  conn
+}
+
+fn build_delta(conn: &Connection) {
+  conn
+    .execute_batch(&format!(
+      "
+    DELETE FROM delta;
+
+    INSERT INTO delta (node_id, name, type, self_size, state)
+    SELECT id, name, type, self_size, 'new'
+    FROM node
+    WHERE snapshot_id = {current}
+      AND id NOT IN (SELECT id FROM node WHERE snapshot_id = {baseline});
+
+    INSERT INTO delta (node_id, name, type, self_size, state)
+    SELECT id, name, type, self_size, 'gone'
+    FROM node
+    WHERE snapshot_id = {baseline}
+      AND id NOT IN (SELECT id FROM node WHERE snapshot_id = {current});
+
+    INSERT INTO delta (node_id, name, type, self_size, state)
+    SELECT id, name, type, self_size, 'survived'
+    FROM node
+    WHERE snapshot_id = {current}
+      AND id IN (SELECT id FROM node WHERE snapshot_id = {baseline});
+    ",
+      current = CURRENT_SNAPSHOT_ID,
+      baseline = BASELINE_SNAPSHOT_ID,
+    ))
+    .expect("failed to build delta table");
+}
@@ -0,0 +1,265 @@
+use crate::execute_batched;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+
+// Builds the dominator tree over the node/edge graph (iterative
+// Cooper-Harvey-Kennedy) and rolls up retained sizes from the leaves.
+// Returns empty if the graph has no root.
+pub(crate) fn compute_retained_rows(conn: &Connection) -> Vec<(u32, u32, i64)> {
+  let self_sizes = load_self_sizes(conn);
+  let successors = load_successors(conn);
+  let predecessors = load_predecessors(conn);
+
+  let root = match find_root(conn) {
+    Some(root) => root,
+    None => return Vec::new(),
+  };
+
+  let postorder = postorder_from(root, &successors);
+  let postorder_number: HashMap<u32, usize> = postorder
+    .iter()
+    .enumerate()
+    .map(|(i, &node)| (node, i))
+    .collect();
+
+  let idom = compute_idom(root, &postorder, &postorder_number, &predecessors);
+  let retained = accumulate_retained_sizes(root, &postorder, &idom, &self_sizes);
+
+  postorder
+    .iter()
+    .map(|&node| (node, idom[&node], retained[&node]))
+    .collect()
+}
+
+// Writes compute_retained_rows's output into the retained table.
+pub fn compute_retained_sizes(conn: &mut Connection) {
+  let rows = compute_retained_rows(conn);
+
+  let tx = conn.transaction().unwrap();
+  execute_batched(
+    &tx,
+    "INSERT INTO retained (node_id, idom, retained_size)",
+    3,
+    &rows,
+    |row| vec![Box::new(row.0), Box::new(row.1), Box::new(row.2)],
+  );
+  tx.commit().expect("failed to commit retained sizes");
+}
+
+fn load_self_sizes(conn: &Connection) -> HashMap<u32, i64> {
+  let mut stmt = conn.prepare("SELECT id, self_size FROM node").unwrap();
+  stmt
+    .query_map(params![], |row| {
+      Ok((row.get::<_, u32>(0)?, row.get::<_, i64>(1)?))
+    })
+    .unwrap()
+    .map(|row| row.unwrap())
+    .collect()
+}
+
+fn load_successors(conn: &Connection) -> HashMap<u32, Vec<u32>> {
+  let mut stmt = conn.prepare("SELECT `from`, `to` FROM edge").unwrap();
+  let mut successors: HashMap<u32, Vec<u32>> = HashMap::new();
+  let rows = stmt
+    .query_map(params![], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, u32>(1)?)))
+    .unwrap();
+  for row in rows {
+    let (from, to) = row.unwrap();
+    successors.entry(from).or_default().push(to);
+  }
+  successors
+}
+
+fn load_predecessors(conn: &Connection) -> HashMap<u32, Vec<u32>> {
+  let mut stmt = conn.prepare("SELECT `from`, `to` FROM edge").unwrap();
+  let mut predecessors: HashMap<u32, Vec<u32>> = HashMap::new();
+  let rows = stmt
+    .query_map(params![], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, u32>(1)?)))
+    .unwrap();
+  for row in rows {
+    let (from, to) = row.unwrap();
+    predecessors.entry(to).or_default().push(from);
+  }
+  predecessors
+}
+
+pub(crate) fn find_root(conn: &Connection) -> Option<u32> {
+  conn
+    .query_row(
+      "SELECT id FROM node WHERE id NOT IN (SELECT DISTINCT `to` FROM edge) ORDER BY id LIMIT 1",
+      params![],
+      |row| row.get(0),
+    )
+    .ok()
+}
+
+// Iterative postorder DFS -- leaves first, root last -- so a deep heap
+// graph can't blow the call stack.
+fn postorder_from(root: u32, successors: &HashMap<u32, Vec<u32>>) -> Vec<u32> {
+  let mut visited = HashSet::new();
+  let mut order = Vec::new();
+  let mut stack: Vec<(u32, usize)> = vec![(root, 0)];
+  visited.insert(root);
+
+  while let Some(&(node, idx)) = stack.last() {
+    let empty = Vec::new();
+    let children = successors.get(&node).unwrap_or(&empty);
+
+    if idx < children.len() {
+      let child = children[idx];
+      stack.last_mut().unwrap().1 += 1;
+      if visited.insert(child) {
+        stack.push((child, 0));
+      }
+    } else {
+      order.push(node);
+      stack.pop();
+    }
+  }
+
+  order
+}
+
+fn compute_idom(
+  root: u32,
+  postorder: &[u32],
+  postorder_number: &HashMap<u32, usize>,
+  predecessors: &HashMap<u32, Vec<u32>>,
+) -> HashMap<u32, u32> {
+  let mut idom: HashMap<u32, u32> = HashMap::new();
+  idom.insert(root, root);
+
+  let empty = Vec::new();
+  let mut changed = true;
+  while changed {
+    changed = false;
+
+    // Reverse postorder, root excluded: root's idom never changes.
+    for &node in postorder.iter().rev().skip(1) {
+      let mut new_idom = None;
+      for &pred in predecessors.get(&node).unwrap_or(&empty) {
+        if !idom.contains_key(&pred) {
+          continue;
+        }
+        new_idom = Some(match new_idom {
+          None => pred,
+          Some(cur) => intersect(cur, pred, &idom, postorder_number),
+        });
+      }
+
+      if let Some(new_idom) = new_idom {
+        if idom.get(&node) != Some(&new_idom) {
+          idom.insert(node, new_idom);
+          changed = true;
+        }
+      }
+    }
+  }
+
+  idom
+}
+
+// Walks both candidate dominators up by postorder number until they meet.
+fn intersect(
+  mut a: u32,
+  mut b: u32,
+  idom: &HashMap<u32, u32>,
+  postorder_number: &HashMap<u32, usize>,
+) -> u32 {
+  while a != b {
+    while postorder_number[&a] < postorder_number[&b] {
+      a = idom[&a];
+    }
+    while postorder_number[&b] < postorder_number[&a] {
+      b = idom[&b];
+    }
+  }
+  a
+}
+
+// retained_size(n) = self_size(n) + sum(retained_size(children dominated by n)).
+fn accumulate_retained_sizes(
+  root: u32,
+  postorder: &[u32],
+  idom: &HashMap<u32, u32>,
+  self_sizes: &HashMap<u32, i64>,
+) -> HashMap<u32, i64> {
+  let mut retained: HashMap<u32, i64> = postorder
+    .iter()
+    .map(|&node| (node, *self_sizes.get(&node).unwrap_or(&0)))
+    .collect();
+
+  for &node in postorder {
+    if node == root {
+      continue;
+    }
+    let size = retained[&node];
+    *retained.get_mut(&idom[&node]).unwrap() += size;
+  }
+
+  retained
+}
+
+#[cfg(test)]
+mod tests {
+  use super::compute_retained_rows;
+  use rusqlite::Connection;
+
+  fn conn_with_graph(nodes: &[(u32, i64)], edges: &[(u32, u32)]) -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch("CREATE TABLE node (id INTEGER PRIMARY KEY, self_size INTEGER); CREATE TABLE edge (`from` INTEGER, `to` INTEGER);")
+      .unwrap();
+    for (id, self_size) in nodes {
+      conn
+        .execute(
+          "INSERT INTO node (id, self_size) VALUES (?1, ?2)",
+          rusqlite::params![id, self_size],
+        )
+        .unwrap();
+    }
+    for (from, to) in edges {
+      conn
+        .execute(
+          "INSERT INTO edge (`from`, `to`) VALUES (?1, ?2)",
+          rusqlite::params![from, to],
+        )
+        .unwrap();
+    }
+    conn
+  }
+
+  #[test]
+  fn diamond_shared_child_is_dominated_by_root() {
+    // 1 -> 2 -> 4
+    // 1 -> 3 -> 4
+    let conn = conn_with_graph(
+      &[(1, 10), (2, 5), (3, 5), (4, 3)],
+      &[(1, 2), (1, 3), (2, 4), (3, 4)],
+    );
+
+    let rows = compute_retained_rows(&conn);
+    let by_node: std::collections::HashMap<u32, (u32, i64)> =
+      rows.into_iter().map(|(id, idom, size)| (id, (idom, size))).collect();
+
+    assert_eq!(by_node[&4].0, 1, "node reachable via two paths is dominated by root");
+    assert_eq!(by_node[&2].1, 5);
+    assert_eq!(by_node[&3].1, 5);
+    assert_eq!(by_node[&4].1, 3);
+    assert_eq!(by_node[&1].1, 23, "root retains everything reachable from it");
+  }
+
+  #[test]
+  fn linear_chain_retains_self_size_downward() {
+    // 1 -> 2 -> 3
+    let conn = conn_with_graph(&[(1, 1), (2, 2), (3, 4)], &[(1, 2), (2, 3)]);
+
+    let rows = compute_retained_rows(&conn);
+    let by_node: std::collections::HashMap<u32, (u32, i64)> =
+      rows.into_iter().map(|(id, idom, size)| (id, (idom, size))).collect();
+
+    assert_eq!(by_node[&1], (1, 7));
+    assert_eq!(by_node[&2], (1, 6));
+    assert_eq!(by_node[&3], (2, 4));
+  }
+}
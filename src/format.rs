@@ -0,0 +1,116 @@
+use crate::ColumnValue;
+use serde_json::json;
+
+// Output encoding for exec_query's result set.
+pub enum OutputFormat {
+  Json,
+  Csv,
+  Table,
+}
+
+impl OutputFormat {
+  pub fn parse(format: &str) -> Result<OutputFormat, String> {
+    match format {
+      "json" => Ok(OutputFormat::Json),
+      "csv" => Ok(OutputFormat::Csv),
+      "table" => Ok(OutputFormat::Table),
+      other => Err(std::format!(
+        "unsupported --format '{}' (expected json, csv or table)",
+        other
+      )),
+    }
+  }
+}
+
+// Writes exec_query's rows out in the requested format as they arrive,
+// so a large result set never has to be buffered in full before the
+// first row is printed.
+pub struct RowWriter {
+  format: OutputFormat,
+  columns: Vec<String>,
+  row_count: usize,
+}
+
+impl RowWriter {
+  pub fn new(format: OutputFormat, columns: Vec<String>) -> Self {
+    RowWriter {
+      format,
+      columns,
+      row_count: 0,
+    }
+  }
+
+  pub fn start(&mut self) {
+    match self.format {
+      OutputFormat::Json => print!("["),
+      OutputFormat::Csv => println!("{}", self.render_csv_row(&self.columns)),
+      OutputFormat::Table => println!("{}", self.columns.join(" | ")),
+    }
+  }
+
+  pub fn write_row(&mut self, values: &[ColumnValue]) {
+    match self.format {
+      OutputFormat::Json => {
+        if self.row_count > 0 {
+          print!(",");
+        }
+        // [name, value] pairs instead of a name-keyed object, so duplicate
+        // column names (self-joins, same-named columns across tables)
+        // don't collapse onto each other.
+        let fields: Vec<serde_json::Value> = self
+          .columns
+          .iter()
+          .zip(values.iter())
+          .map(|(name, value)| json!([name, column_value_to_json(value)]))
+          .collect();
+        print!("{}", json!(fields));
+      }
+      OutputFormat::Csv => {
+        let cells: Vec<String> = values.iter().map(ColumnValue::to_plain_string).collect();
+        println!("{}", self.render_csv_row(&cells));
+      }
+      OutputFormat::Table => {
+        let cells: Vec<String> = values.iter().map(ColumnValue::to_plain_string).collect();
+        println!("{}", cells.join(" | "));
+      }
+    }
+    self.row_count += 1;
+  }
+
+  pub fn finish(&mut self) {
+    if let OutputFormat::Json = self.format {
+      println!("]");
+    }
+  }
+
+  fn render_csv_row<S: AsRef<str>>(&self, cells: &[S]) -> String {
+    cells
+      .iter()
+      .map(|cell| csv_field(cell.as_ref()))
+      .collect::<Vec<_>>()
+      .join(",")
+  }
+}
+
+fn csv_field(field: &str) -> String {
+  if field.contains(',') || field.contains('"') || field.contains('\n') {
+    std::format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+fn column_value_to_json(value: &ColumnValue) -> serde_json::Value {
+  match value {
+    ColumnValue::Integer(i) => json!(i),
+    ColumnValue::Real(r) => json!(r),
+    ColumnValue::Text(t) => json!(t),
+    ColumnValue::Blob(b) => json!(hex_encode(b)),
+    ColumnValue::Null => serde_json::Value::Null,
+  }
+}
+
+// Lower-case hex encoding used for Blob columns across every format.
+pub fn hex_encode(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| std::format!("{:02x}", b)).collect()
+}
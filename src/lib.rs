@@ -1,7 +1,60 @@
+mod diff;
+mod dominator;
+mod format;
+mod migration;
+mod pragma;
+mod trace;
+
 use core::fmt;
-use rusqlite::{params, types::ValueRef, Connection};
+use rusqlite::{params, params_from_iter, types::ToSql, types::ValueRef, Connection};
 use serde_json::Value;
-use std::{collections::HashMap, format, fs, path::Path};
+use std::{collections::HashMap, fs, path::Path};
+
+pub use diff::{diff_db_name, diff_snapshots};
+pub use dominator::compute_retained_sizes;
+pub use format::{OutputFormat, RowWriter};
+pub use migration::{current_schema_version, run_migrations, SCHEMA_VERSION};
+pub use pragma::ConnectionOptions;
+pub use trace::{path_to_root, reachable_of_type, RetainingStep};
+
+// Max rows folded into a single multi-row `INSERT ... VALUES (...), (...)`.
+// Keeps sqlite's parameter count bounded while still cutting the per-row
+// statement re-parse cost way down.
+pub(crate) const INSERT_BATCH_SIZE: usize = 500;
+
+// Inserts `rows` in batches of up to `INSERT_BATCH_SIZE`, reusing one
+// prepared statement per batch size via `prepare_cached` instead of
+// re-parsing an `INSERT` per row.
+pub(crate) fn execute_batched<T>(
+  conn: &Connection,
+  insert_prefix: &str,
+  arity: usize,
+  rows: &[T],
+  to_params: impl Fn(&T) -> Vec<Box<dyn ToSql>>,
+) {
+  for batch in rows.chunks(INSERT_BATCH_SIZE) {
+    let values_clause = (0..batch.len())
+      .map(|row| {
+        let placeholders: Vec<String> = (0..arity)
+          .map(|col| format!("?{}", row * arity + col + 1))
+          .collect();
+        format!("({})", placeholders.join(", "))
+      })
+      .collect::<Vec<_>>()
+      .join(", ");
+
+    let sql = format!("{} VALUES {}", insert_prefix, values_clause);
+    let mut stmt = conn
+      .prepare_cached(&sql)
+      .expect("failed to prepare batch insert");
+
+    let params: Vec<Box<dyn ToSql>> = batch.iter().flat_map(&to_params).collect();
+    let param_refs: Vec<&dyn ToSql> = params.iter().map(|p| p.as_ref()).collect();
+    stmt
+      .execute(params_from_iter(param_refs))
+      .expect("failed to insert batch");
+  }
+}
 
 pub fn assoc_db_name(heap_file: &str) -> String {
   let path = Path::new(heap_file);
@@ -16,9 +69,14 @@ pub fn open_db(path: &str) -> Connection {
   Connection::open(&path).expect("unable to open db")
 }
 
+// Opens the `.db3` associated with `heap_file`, bringing its schema up to
+// SCHEMA_VERSION first so a db created by an older heapquery doesn't
+// silently keep a stale layout.
 pub fn open_assoc_db(heap_file: &str) -> Connection {
   let db_name = assoc_db_name(heap_file);
-  open_db(&db_name)
+  let mut conn = open_db(&db_name);
+  run_migrations(&mut conn);
+  conn
 }
 
 pub fn read_heap_file(heap_file: &str) -> Value {
@@ -26,37 +84,7 @@ pub fn read_heap_file(heap_file: &str) -> Value {
   serde_json::from_str(heap_data.as_str()).expect("deformed heap file")
 }
 
-pub fn init_schema(conn: &Connection) {
-  conn
-    .execute_batch(
-      "
-    CREATE TABLE IF NOT EXISTS node (
-      id INTEGER PRIMARY KEY,
-      name VARCHAR(50),
-      type VARCHAR(50),
-      self_size INTEGER,
-      children_count INTEGER
-    );
-    
-    CREATE TABLE IF NOT EXISTS edge (
-      `from` INTEGER,
-      `to` INTEGER,
-      type VARCHAR(50),
-      name_or_index VARCHAR(50)
-    );
-
-    CREATE TABLE IF NOT EXISTS location (
-      node_id INTEGER,
-      script_id INTEGER,
-      line INTEGER,
-      col INTEGER
-    );
-    ",
-    )
-    .expect("unable to init schema");
-}
-
-pub fn insert_nodes(heap_json: &Value, conn: &mut Connection) {
+pub fn insert_nodes(heap_json: &Value, conn: &mut Connection, snapshot_id: i64) {
   let meta = &heap_json["snapshot"]["meta"];
 
   let node_fields = meta["node_fields"].as_array().unwrap();
@@ -68,8 +96,7 @@ pub fn insert_nodes(heap_json: &Value, conn: &mut Connection) {
   let node_field_values_len = node_field_values.len();
 
   let mut i = 0;
-
-  let tx = conn.transaction().unwrap();
+  let mut rows = Vec::new();
 
   while i < node_field_values_len {
     let mut node = HashMap::new();
@@ -94,26 +121,39 @@ pub fn insert_nodes(heap_json: &Value, conn: &mut Connection) {
       i += 1;
     });
 
-    tx.execute(
-      "
-    INSERT INTO node (id, name, type, self_size, children_count)
-    VALUES (?1, ?2, ?3, ?4, ?5)
-    ",
-      params![
-        node["id"].as_u64().unwrap() as u32,
-        node["name"].as_str().unwrap(),
-        node["type"].as_str().unwrap(),
-        node["self_size"].as_u64().unwrap() as u32,
-        node["edge_count"].as_u64().unwrap() as u32
-      ],
-    )
-    .expect("failed to insert node");
+    rows.push((
+      node["id"].as_u64().unwrap() as u32,
+      node["name"].as_str().unwrap().to_string(),
+      node["type"].as_str().unwrap().to_string(),
+      node["self_size"].as_u64().unwrap() as u32,
+      node["edge_count"].as_u64().unwrap() as u32,
+      snapshot_id,
+    ));
   }
 
+  let tx = conn.transaction().unwrap();
+
+  execute_batched(
+    &tx,
+    "INSERT INTO node (id, name, type, self_size, children_count, snapshot_id)",
+    6,
+    &rows,
+    |row| {
+      vec![
+        Box::new(row.0),
+        Box::new(row.1.clone()),
+        Box::new(row.2.clone()),
+        Box::new(row.3),
+        Box::new(row.4),
+        Box::new(row.5),
+      ]
+    },
+  );
+
   tx.commit().expect("failed to commit");
 }
 
-pub fn insert_edges(heap_json: &Value, conn: &mut Connection) {
+pub fn insert_edges(heap_json: &Value, conn: &mut Connection, snapshot_id: i64) {
   let meta = &heap_json["snapshot"]["meta"];
   let node_fields = meta["node_fields"].as_array().unwrap();
   let node_fields_len = node_fields.len();
@@ -132,8 +172,7 @@ pub fn insert_edges(heap_json: &Value, conn: &mut Connection) {
 
   let mut node_i = 0;
   let mut edge_i = 0;
-
-  let tx = conn.transaction().unwrap();
+  let mut rows = Vec::new();
 
   while node_i < node_field_values_len {
     let node_id = node_field_values[node_i + node_id_ofst].as_u64().unwrap();
@@ -172,32 +211,44 @@ pub fn insert_edges(heap_json: &Value, conn: &mut Connection) {
         .as_u64()
         .unwrap() as u32;
 
-      tx.execute(
-        "
-      INSERT INTO edge (`from`, `to`, type, name_or_index)
-      VALUES (?1, ?2, ?3, ?4)
-      ",
-        params![
-          node_id as u32,
-          to_node_id,
-          edge["type"].as_str().unwrap(),
-          if edge["name_or_index"].is_number() {
-            edge["name_or_index"].as_u64().unwrap().to_string()
-          } else {
-            edge["name_or_index"].as_str().unwrap().to_string()
-          },
-        ],
-      )
-      .expect("failed to insert node");
+      rows.push((
+        node_id as u32,
+        to_node_id,
+        edge["type"].as_str().unwrap().to_string(),
+        if edge["name_or_index"].is_number() {
+          edge["name_or_index"].as_u64().unwrap().to_string()
+        } else {
+          edge["name_or_index"].as_str().unwrap().to_string()
+        },
+        snapshot_id,
+      ));
     }
 
     node_i += node_fields_len;
   }
 
+  let tx = conn.transaction().unwrap();
+
+  execute_batched(
+    &tx,
+    "INSERT INTO edge (`from`, `to`, type, name_or_index, snapshot_id)",
+    5,
+    &rows,
+    |row| {
+      vec![
+        Box::new(row.0),
+        Box::new(row.1),
+        Box::new(row.2.clone()),
+        Box::new(row.3.clone()),
+        Box::new(row.4),
+      ]
+    },
+  );
+
   tx.commit().expect("failed to commit");
 }
 
-pub fn insert_locations(heap_json: &Value, conn: &mut Connection) {
+pub fn insert_locations(heap_json: &Value, conn: &mut Connection, snapshot_id: i64) {
   let loc_field_values = heap_json["locations"].as_array().unwrap();
   let loc_field_values_len = loc_field_values.len();
 
@@ -206,7 +257,7 @@ pub fn insert_locations(heap_json: &Value, conn: &mut Connection) {
   // below values are noticeable to keep sync with the fields order in heapsnapshot
   let node_id_ofst = 2;
 
-  let tx = conn.transaction().unwrap();
+  let mut rows = Vec::new();
 
   let mut i = 0;
   while i < loc_field_values_len {
@@ -225,16 +276,33 @@ pub fn insert_locations(heap_json: &Value, conn: &mut Connection) {
     let col = loc_field_values[i].as_u64().unwrap();
     i += 1;
 
-    tx.execute(
-      "
-    INSERT INTO location (node_id, script_id, line, col)
-    VALUES (?1, ?2, ?3, ?4)
-    ",
-      params![node_id as u32, script_id as u32, line as u32, col as u32,],
-    )
-    .expect("failed to insert node");
+    rows.push((
+      node_id as u32,
+      script_id as u32,
+      line as u32,
+      col as u32,
+      snapshot_id,
+    ));
   }
 
+  let tx = conn.transaction().unwrap();
+
+  execute_batched(
+    &tx,
+    "INSERT INTO location (node_id, script_id, line, col, snapshot_id)",
+    5,
+    &rows,
+    |row| {
+      vec![
+        Box::new(row.0),
+        Box::new(row.1),
+        Box::new(row.2),
+        Box::new(row.3),
+        Box::new(row.4),
+      ]
+    },
+  );
+
   tx.commit().expect("failed to commit");
 }
 
@@ -242,43 +310,101 @@ pub enum ColumnValue {
   Integer(i64),
   Real(f64),
   Text(String),
+  Blob(Vec<u8>),
   Null,
 }
 
-impl fmt::Debug for ColumnValue {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl ColumnValue {
+  // Plain-text rendering shared by the csv and table formats. Blobs are
+  // hex-encoded since there's no binary-safe column separator to lean on.
+  pub fn to_plain_string(&self) -> String {
     match self {
-      ColumnValue::Integer(i) => write!(f, "{}", i),
-      ColumnValue::Real(r) => write!(f, "{}", r),
-      ColumnValue::Text(t) => write!(f, "{}", t),
-      ColumnValue::Null => write!(f, "{}", "null"),
+      ColumnValue::Integer(i) => i.to_string(),
+      ColumnValue::Real(r) => r.to_string(),
+      ColumnValue::Text(t) => t.clone(),
+      ColumnValue::Blob(b) => format::hex_encode(b),
+      ColumnValue::Null => "null".to_string(),
     }
   }
 }
 
-pub fn exec_query(conn: &Connection, sql: &str) {
-  println!("run sql: {}", sql);
-
-  let mut stmt = conn.prepare(sql).expect("failed to prepare query");
-  let rows = stmt
-    .query_map(params![], |row| {
-      let mut ret = HashMap::new();
-      row.column_names().into_iter().enumerate().for_each(|item| {
-        let v = row.get_raw_checked(item.0).unwrap();
-        let cv = match v {
-          ValueRef::Integer(i) => ColumnValue::Integer(i),
-          ValueRef::Real(r) => ColumnValue::Real(r),
-          ValueRef::Text(t) => ColumnValue::Text(String::from_utf8(t.to_owned()).unwrap()),
-          ValueRef::Null => ColumnValue::Null,
-          ValueRef::Blob(_) => unimplemented!("unsupported value type: Blob"),
-        };
-        ret.insert(item.1.to_string(), cv);
-      });
-      Ok(ret)
-    })
-    .expect("failed to run query");
+impl From<ValueRef<'_>> for ColumnValue {
+  fn from(v: ValueRef) -> Self {
+    match v {
+      ValueRef::Integer(i) => ColumnValue::Integer(i),
+      ValueRef::Real(r) => ColumnValue::Real(r),
+      ValueRef::Text(t) => ColumnValue::Text(String::from_utf8_lossy(t).into_owned()),
+      ValueRef::Blob(b) => ColumnValue::Blob(b.to_vec()),
+      ValueRef::Null => ColumnValue::Null,
+    }
+  }
+}
+
+impl fmt::Debug for ColumnValue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.to_plain_string())
+  }
+}
+
+// Runs sql and streams the result set out as format, writing each row as
+// it arrives rather than buffering the whole result set in memory.
+// Returns Err instead of panicking on malformed SQL, so a bad --query
+// reports an error instead of aborting the process.
+pub fn exec_query(conn: &Connection, sql: &str, format: OutputFormat) -> rusqlite::Result<()> {
+  let mut stmt = conn.prepare(sql)?;
+  let columns: Vec<String> = stmt
+    .column_names()
+    .into_iter()
+    .map(|name| name.to_string())
+    .collect();
+
+  let mut writer = RowWriter::new(format, columns.clone());
+  writer.start();
+
+  let mut rows = stmt.query(params![])?;
+  while let Some(row) = rows.next()? {
+    let values: Vec<ColumnValue> = (0..columns.len())
+      .map(|i| row.get_raw_checked(i).map(ColumnValue::from))
+      .collect::<rusqlite::Result<Vec<_>>>()?;
+    writer.write_row(&values);
+  }
+
+  writer.finish();
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{exec_query, OutputFormat};
+  use rusqlite::Connection;
+
+  fn conn_with_blob() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch("CREATE TABLE t (id INTEGER, payload BLOB);")
+      .unwrap();
+    conn
+      .execute(
+        "INSERT INTO t (id, payload) VALUES (1, ?1)",
+        rusqlite::params![vec![0xde_u8, 0xad, 0xbe, 0xef]],
+      )
+      .unwrap();
+    conn
+  }
+
+  #[test]
+  fn blob_columns_are_handled_in_every_format() {
+    let conn = conn_with_blob();
+    for format in [OutputFormat::Json, OutputFormat::Csv, OutputFormat::Table] {
+      exec_query(&conn, "SELECT id, payload FROM t", format)
+        .expect("a blob column must not panic or error exec_query");
+    }
+  }
 
-  for r in rows {
-    println!("{:?}", r.unwrap());
+  #[test]
+  fn malformed_sql_returns_err_instead_of_panicking() {
+    let conn = conn_with_blob();
+    let result = exec_query(&conn, "SELEKT * FROM t", OutputFormat::Json);
+    assert!(result.is_err());
   }
 }
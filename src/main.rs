@@ -1,7 +1,8 @@
 use clap::{App, Arg};
 use heapquery::{
-  exec_query, init_schema, insert_edges, insert_locations, insert_nodes, open_assoc_db,
-  read_heap_file, setup_db_if_needed,
+  assoc_db_name, compute_retained_sizes, diff_snapshots, exec_query, insert_edges,
+  insert_locations, insert_nodes, open_assoc_db, path_to_root, read_heap_file,
+  setup_db_if_needed, ConnectionOptions, OutputFormat,
 };
 use serde_json::Value;
 
@@ -18,6 +19,21 @@ fn main() {
         .help("The heap file produced from `v8.getHeapSnapshot`")
         .takes_value(true),
     )
+    .arg(
+      Arg::with_name("baseline")
+        .long("baseline")
+        .value_name("baseline")
+        .help("An earlier heap file to diff `--heap` against, to spot what's leaking")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("trace")
+        .long("trace")
+        .value_name("node id")
+        .help("Print the shortest retaining path from the GC root down to this node id")
+        .conflicts_with("baseline")
+        .takes_value(true),
+    )
     .arg(
       Arg::with_name("query")
         .long("query")
@@ -25,20 +41,62 @@ fn main() {
         .help("The SQL to query your data")
         .takes_value(true),
     )
+    .arg(
+      Arg::with_name("format")
+        .long("format")
+        .value_name("format")
+        .help("Output format for --query: json, csv or table")
+        .possible_values(&["json", "csv", "table"])
+        .default_value("table")
+        .takes_value(true),
+    )
     .get_matches();
 
   let heap_file = matches.value_of("heap").unwrap();
-  if setup_db_if_needed(heap_file) {
-    let heap_json: Value = read_heap_file(heap_file);
-    let mut conn = open_assoc_db(heap_file);
-    init_schema(&conn);
-    insert_nodes(&heap_json, &mut conn);
-    insert_edges(&heap_json, &mut conn);
-    insert_locations(&heap_json, &mut conn);
+
+  let conn = if let Some(baseline_heap) = matches.value_of("baseline") {
+    diff_snapshots(heap_file, baseline_heap)
+  } else {
+    if setup_db_if_needed(&assoc_db_name(heap_file)) {
+      let heap_json: Value = read_heap_file(heap_file);
+      let mut conn = open_assoc_db(heap_file);
+      ConnectionOptions::for_import().apply(&conn);
+      insert_nodes(&heap_json, &mut conn, 0);
+      insert_edges(&heap_json, &mut conn, 0);
+      insert_locations(&heap_json, &mut conn, 0);
+      compute_retained_sizes(&mut conn);
+      ConnectionOptions::for_query().apply(&conn);
+    }
+    open_assoc_db(heap_file)
+  };
+
+  if let Some(node_id) = matches.value_of("trace") {
+    let node_id: u32 = node_id.parse().expect("--trace expects a node id");
+    match path_to_root(&conn, node_id) {
+      Some(path) => {
+        for step in path {
+          match (step.via_edge_type, step.via_name_or_index) {
+            (Some(edge_type), Some(name_or_index)) => println!(
+              "--[{} {}]--> {} ({}) #{}",
+              edge_type, name_or_index, step.name, step.node_type, step.node_id
+            ),
+            _ => println!("{} ({}) #{}", step.name, step.node_type, step.node_id),
+          }
+        }
+      }
+      None => println!("no retaining path found for node {}", node_id),
+    }
   }
 
   if let Some(query) = matches.value_of("query") {
-    let conn = open_assoc_db(heap_file);
-    exec_query(&conn, query)
+    let format = OutputFormat::parse(matches.value_of("format").unwrap()).unwrap_or_else(|err| {
+      eprintln!("{}", err);
+      std::process::exit(1);
+    });
+
+    if let Err(err) = exec_query(&conn, query, format) {
+      eprintln!("query failed: {}", err);
+      std::process::exit(1);
+    }
   }
 }
@@ -0,0 +1,200 @@
+use crate::{dominator::compute_retained_rows, execute_batched};
+use rusqlite::{params, Connection};
+
+// Bump this and append a migration below whenever node/edge/location (or
+// any derived table) changes shape.
+pub const SCHEMA_VERSION: i32 = 3;
+
+type Migration = fn(&Connection);
+
+fn migrations() -> Vec<(i32, Migration)> {
+  vec![(1, migrate_to_v1), (2, migrate_to_v2), (3, migrate_to_v3)]
+}
+
+fn migrate_to_v1(conn: &Connection) {
+  conn
+    .execute_batch(
+      "
+    CREATE TABLE IF NOT EXISTS node (
+      id INTEGER PRIMARY KEY,
+      name VARCHAR(50),
+      type VARCHAR(50),
+      self_size INTEGER,
+      children_count INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS edge (
+      `from` INTEGER,
+      `to` INTEGER,
+      type VARCHAR(50),
+      name_or_index VARCHAR(50)
+    );
+
+    CREATE TABLE IF NOT EXISTS location (
+      node_id INTEGER,
+      script_id INTEGER,
+      line INTEGER,
+      col INTEGER
+    );
+    ",
+    )
+    .expect("failed to apply migration to schema version 1");
+}
+
+fn migrate_to_v2(conn: &Connection) {
+  conn
+    .execute_batch(
+      "
+    CREATE TABLE IF NOT EXISTS retained (
+      node_id INTEGER PRIMARY KEY,
+      idom INTEGER,
+      retained_size INTEGER
+    );
+    ",
+    )
+    .expect("failed to apply migration to schema version 2");
+
+  // Backfill for a db3 written before this table existed -- without this,
+  // a pre-existing import's retained table stays empty forever, since
+  // compute_retained_sizes is otherwise only called right after a fresh
+  // import.
+  let rows = compute_retained_rows(conn);
+  execute_batched(
+    conn,
+    "INSERT INTO retained (node_id, idom, retained_size)",
+    3,
+    &rows,
+    |row| vec![Box::new(row.0), Box::new(row.1), Box::new(row.2)],
+  );
+}
+
+fn migrate_to_v3(conn: &Connection) {
+  conn
+    .execute_batch(
+      "
+    ALTER TABLE edge ADD COLUMN snapshot_id INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE location ADD COLUMN snapshot_id INTEGER NOT NULL DEFAULT 0;
+
+    CREATE TABLE node_v3 (
+      id INTEGER,
+      name VARCHAR(50),
+      type VARCHAR(50),
+      self_size INTEGER,
+      children_count INTEGER,
+      snapshot_id INTEGER NOT NULL DEFAULT 0,
+      PRIMARY KEY (id, snapshot_id)
+    );
+    INSERT INTO node_v3 (id, name, type, self_size, children_count, snapshot_id)
+      SELECT id, name, type, self_size, children_count, 0 FROM node;
+    DROP TABLE node;
+    ALTER TABLE node_v3 RENAME TO node;
+
+    CREATE TABLE IF NOT EXISTS delta (
+      node_id INTEGER,
+      name VARCHAR(50),
+      type VARCHAR(50),
+      self_size INTEGER,
+      state VARCHAR(10)
+    );
+    ",
+    )
+    .expect("failed to apply migration to schema version 3");
+}
+
+// PRAGMA user_version. A freshly created (empty) sqlite file reads 0.
+pub fn current_schema_version(conn: &Connection) -> i32 {
+  conn
+    .query_row("PRAGMA user_version", params![], |row| row.get(0))
+    .expect("failed to read schema version")
+}
+
+fn set_schema_version(conn: &Connection, version: i32) {
+  conn
+    .execute_batch(&format!("PRAGMA user_version = {}", version))
+    .expect("failed to persist schema version");
+}
+
+// Brings a db3 up to SCHEMA_VERSION, running every pending migration in
+// order inside a single transaction so a crash mid-upgrade can't leave a
+// half-migrated file behind.
+pub fn run_migrations(conn: &mut Connection) {
+  let from = current_schema_version(conn);
+  if from >= SCHEMA_VERSION {
+    return;
+  }
+
+  let tx = conn
+    .transaction()
+    .expect("failed to start migration transaction");
+  for (version, migrate) in migrations() {
+    if version > from {
+      migrate(&tx);
+    }
+  }
+  set_schema_version(&tx, SCHEMA_VERSION);
+  tx.commit().expect("failed to commit migrations");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{current_schema_version, run_migrations, SCHEMA_VERSION};
+  use rusqlite::Connection;
+
+  fn table_names(conn: &Connection) -> Vec<String> {
+    let mut stmt = conn
+      .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+      .unwrap();
+    stmt
+      .query_map([], |row| row.get::<_, String>(0))
+      .unwrap()
+      .map(|row| row.unwrap())
+      .collect()
+  }
+
+  fn column_names(conn: &Connection, table: &str) -> Vec<String> {
+    let mut stmt = conn
+      .prepare(&format!("PRAGMA table_info({})", table))
+      .unwrap();
+    stmt
+      .query_map([], |row| row.get::<_, String>(1))
+      .unwrap()
+      .map(|row| row.unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn brings_a_bare_db_up_to_the_current_schema() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    assert_eq!(current_schema_version(&conn), 0);
+
+    run_migrations(&mut conn);
+
+    assert_eq!(current_schema_version(&conn), SCHEMA_VERSION);
+    assert_eq!(
+      table_names(&conn),
+      vec!["delta", "edge", "location", "node", "retained"]
+    );
+    assert!(column_names(&conn, "node").contains(&"snapshot_id".to_string()));
+    assert!(column_names(&conn, "edge").contains(&"snapshot_id".to_string()));
+  }
+
+  #[test]
+  fn second_call_is_a_no_op() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    run_migrations(&mut conn);
+    conn
+      .execute(
+        "INSERT INTO node (id, name, type, self_size, children_count, snapshot_id) VALUES (1, 'a', 'object', 1, 0, 0)",
+        [],
+      )
+      .unwrap();
+
+    run_migrations(&mut conn);
+
+    assert_eq!(current_schema_version(&conn), SCHEMA_VERSION);
+    let count: i64 = conn
+      .query_row("SELECT COUNT(*) FROM node", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(count, 1, "re-running migrations must not touch existing data");
+  }
+}
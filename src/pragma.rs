@@ -0,0 +1,41 @@
+use rusqlite::Connection;
+use std::time::Duration;
+
+// Pragma tuning applied to a connection. Importing wants speed over
+// durability (the db3 can always be rebuilt from the heap file); querying
+// wants sqlite's normal safe defaults back.
+pub struct ConnectionOptions {
+  pub synchronous: &'static str,
+  pub journal_mode: &'static str,
+  pub busy_timeout: Duration,
+}
+
+impl ConnectionOptions {
+  pub fn for_import() -> Self {
+    ConnectionOptions {
+      synchronous: "OFF",
+      journal_mode: "MEMORY",
+      busy_timeout: Duration::from_secs(60),
+    }
+  }
+
+  pub fn for_query() -> Self {
+    ConnectionOptions {
+      synchronous: "FULL",
+      journal_mode: "DELETE",
+      busy_timeout: Duration::from_secs(5),
+    }
+  }
+
+  pub fn apply(&self, conn: &Connection) {
+    conn
+      .busy_timeout(self.busy_timeout)
+      .expect("failed to set busy_timeout");
+    conn
+      .pragma_update(None, "synchronous", &self.synchronous)
+      .expect("failed to set synchronous pragma");
+    conn
+      .pragma_update(None, "journal_mode", &self.journal_mode)
+      .expect("failed to set journal_mode pragma");
+  }
+}
@@ -0,0 +1,210 @@
+use crate::dominator::find_root;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// One hop of a retaining path: the node reached, and (unless it's the GC
+/// root) the edge that was followed to reach it from the previous node.
+pub struct RetainingStep {
+  pub node_id: u32,
+  pub name: String,
+  pub node_type: String,
+  pub via_edge_type: Option<String>,
+  pub via_name_or_index: Option<String>,
+}
+
+// BFS over the reverse edge graph from node_id to the root, then walks
+// the BFS parent pointers back out into a root-to-node_id path. None if
+// node_id doesn't exist or isn't reachable from the root.
+pub fn path_to_root(conn: &Connection, node_id: u32) -> Option<Vec<RetainingStep>> {
+  let root = find_root(conn)?;
+  let predecessors = load_predecessor_edges(conn);
+  let node_info = load_node_info(conn);
+
+  if !node_info.contains_key(&node_id) {
+    return None;
+  }
+
+  // bfs_parent[p] = (c, edge_type, name_or_index) means p was discovered
+  // as a predecessor of c, i.e. the real graph has an edge p -(type, name)-> c.
+  let mut bfs_parent: HashMap<u32, (u32, String, String)> = HashMap::new();
+  let mut visited = HashSet::new();
+  let mut queue = VecDeque::new();
+  visited.insert(node_id);
+  queue.push_back(node_id);
+
+  while let Some(current) = queue.pop_front() {
+    if current == root {
+      break;
+    }
+    if let Some(preds) = predecessors.get(&current) {
+      for (from, edge_type, name_or_index) in preds {
+        if visited.insert(*from) {
+          bfs_parent.insert(*from, (current, edge_type.clone(), name_or_index.clone()));
+          queue.push_back(*from);
+        }
+      }
+    }
+  }
+
+  if node_id != root && !bfs_parent.contains_key(&root) {
+    return None;
+  }
+
+  let mut path = Vec::new();
+  let (name, node_type) = node_info.get(&root).unwrap().clone();
+  path.push(RetainingStep {
+    node_id: root,
+    name,
+    node_type,
+    via_edge_type: None,
+    via_name_or_index: None,
+  });
+
+  let mut current = root;
+  while current != node_id {
+    let (next, edge_type, name_or_index) = bfs_parent.get(&current).unwrap().clone();
+    let (name, node_type) = node_info.get(&next).unwrap().clone();
+    path.push(RetainingStep {
+      node_id: next,
+      name,
+      node_type,
+      via_edge_type: Some(edge_type),
+      via_name_or_index: Some(name_or_index),
+    });
+    current = next;
+  }
+
+  Some(path)
+}
+
+// Every node of node_type reachable from from_node within max_hops edges.
+pub fn reachable_of_type(
+  conn: &Connection,
+  from_node: u32,
+  node_type: &str,
+  max_hops: u32,
+) -> Vec<(u32, String)> {
+  let mut stmt = conn
+    .prepare(
+      "
+    WITH RECURSIVE reachable(id, hops) AS (
+      SELECT ?1, 0
+      UNION
+      SELECT edge.`to`, reachable.hops + 1
+      FROM edge
+      JOIN reachable ON edge.`from` = reachable.id
+      WHERE reachable.hops < ?2
+    )
+    SELECT DISTINCT node.id, node.name
+    FROM reachable
+    JOIN node ON node.id = reachable.id
+    WHERE node.type = ?3 AND node.id != ?1
+    ORDER BY node.id
+    ",
+    )
+    .expect("failed to prepare reachability query");
+
+  stmt
+    .query_map(params![from_node, max_hops, node_type], |row| {
+      Ok((row.get(0)?, row.get(1)?))
+    })
+    .expect("failed to run reachability query")
+    .map(|row| row.unwrap())
+    .collect()
+}
+
+fn load_predecessor_edges(conn: &Connection) -> HashMap<u32, Vec<(u32, String, String)>> {
+  let mut stmt = conn
+    .prepare("SELECT `from`, `to`, type, name_or_index FROM edge")
+    .unwrap();
+  let mut predecessors: HashMap<u32, Vec<(u32, String, String)>> = HashMap::new();
+  let rows = stmt
+    .query_map(params![], |row| {
+      Ok((
+        row.get::<_, u32>(0)?,
+        row.get::<_, u32>(1)?,
+        row.get::<_, String>(2)?,
+        row.get::<_, String>(3)?,
+      ))
+    })
+    .unwrap();
+  for row in rows {
+    let (from, to, edge_type, name_or_index) = row.unwrap();
+    predecessors
+      .entry(to)
+      .or_default()
+      .push((from, edge_type, name_or_index));
+  }
+  predecessors
+}
+
+fn load_node_info(conn: &Connection) -> HashMap<u32, (String, String)> {
+  let mut stmt = conn.prepare("SELECT id, name, type FROM node").unwrap();
+  stmt
+    .query_map(params![], |row| {
+      Ok((row.get::<_, u32>(0)?, (row.get(1)?, row.get(2)?)))
+    })
+    .unwrap()
+    .map(|row| row.unwrap())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::path_to_root;
+  use rusqlite::Connection;
+
+  fn conn_with_graph(nodes: &[(u32, &str, &str)], edges: &[(u32, u32, &str, &str)]) -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE node (id INTEGER PRIMARY KEY, name TEXT, type TEXT);
+         CREATE TABLE edge (`from` INTEGER, `to` INTEGER, type TEXT, name_or_index TEXT);",
+      )
+      .unwrap();
+    for (id, name, node_type) in nodes {
+      conn
+        .execute(
+          "INSERT INTO node (id, name, type) VALUES (?1, ?2, ?3)",
+          rusqlite::params![id, name, node_type],
+        )
+        .unwrap();
+    }
+    for (from, to, edge_type, name_or_index) in edges {
+      conn
+        .execute(
+          "INSERT INTO edge (`from`, `to`, type, name_or_index) VALUES (?1, ?2, ?3, ?4)",
+          rusqlite::params![from, to, edge_type, name_or_index],
+        )
+        .unwrap();
+    }
+    conn
+  }
+
+  #[test]
+  fn finds_shortest_path_from_root() {
+    // 1 (root) --a--> 2 --b--> 3
+    let conn = conn_with_graph(
+      &[(1, "root", "object"), (2, "obj2", "object"), (3, "target", "object")],
+      &[(1, 2, "property", "a"), (2, 3, "property", "b")],
+    );
+
+    let path = path_to_root(&conn, 3).expect("node 3 should be reachable from root");
+    let ids: Vec<u32> = path.iter().map(|step| step.node_id).collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(path[0].via_edge_type, None);
+    assert_eq!(path[1].via_name_or_index.as_deref(), Some("a"));
+    assert_eq!(path[2].via_name_or_index.as_deref(), Some("b"));
+  }
+
+  #[test]
+  fn unreachable_node_returns_none() {
+    // 1 (root) --a--> 2, with 3 disconnected.
+    let conn = conn_with_graph(
+      &[(1, "root", "object"), (2, "obj2", "object"), (3, "orphan", "object")],
+      &[(1, 2, "property", "a")],
+    );
+
+    assert!(path_to_root(&conn, 3).is_none());
+  }
+}